@@ -0,0 +1,319 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use globset::Glob;
+use serde::Deserialize;
+use walkdir::WalkDir;
+
+use crate::Result;
+
+/// A single entry in `writekit.yaml`'s rule list, e.g.:
+///
+/// ```yaml
+/// rules:
+///   - input: "*.md"
+///     output: pdf
+///     command: "marp {input} --pdf -o {output}"
+///     change: ["*/_includes/*.md"]
+///     ignore: ["*.draft.md"]
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+pub struct Rule {
+    input: String,
+    output: String,
+    command: String,
+    // Extra patterns, beyond `input`, whose changes should also trigger
+    // this rule (e.g. a shared include file the output depends on).
+    #[serde(default)]
+    change: Vec<String>,
+    // Patterns carved out of `input`/`change` that should never trigger
+    // this rule, even though they'd otherwise match (e.g. drafts).
+    #[serde(default)]
+    ignore: Vec<String>,
+}
+
+/// True if `pattern` (a glob, as in `input`/`change`/`ignore`) matches `path`.
+fn glob_matches(pattern: &str, path: &Path) -> bool {
+    Glob::new(pattern)
+        .map(|glob| glob.compile_matcher().is_match(path))
+        .unwrap_or(false)
+}
+
+impl Rule {
+    /// True if `path` is ignored by this rule's `ignore` patterns.
+    fn ignored(&self, path: &Path) -> bool {
+        self.ignore
+            .iter()
+            .any(|pattern| glob_matches(pattern, path))
+    }
+
+    /// True if `path` is itself an input this rule converts, i.e. it has
+    /// its own `{output}` (matches `input`, isn't a `change` dependency,
+    /// and isn't carved out by `ignore`). Unlike `matches_change`, this is
+    /// a path `output_path`/`command_args` can be called on directly.
+    fn matches_input(&self, path: &Path) -> bool {
+        glob_matches(&self.input, path) && !self.ignored(path) && !self.matches_change(path)
+    }
+
+    /// True if `path` is one of this rule's extra `change` dependencies
+    /// (e.g. a shared include) rather than an input in its own right --
+    /// it has no `{output}` of its own, see `primary_inputs`.
+    fn matches_change(&self, path: &Path) -> bool {
+        self.change
+            .iter()
+            .any(|pattern| glob_matches(pattern, path))
+            && !self.ignored(path)
+    }
+
+    /// True if `path` should trigger this rule at all, either directly
+    /// (`matches_input`) or via a `change` dependency (`matches_change`).
+    fn matches(&self, path: &Path) -> bool {
+        self.matches_input(path) || self.matches_change(path)
+    }
+
+    /// Every already-existing file under `base` that this rule converts
+    /// directly (`matches_input`) -- used to resolve a `change` match
+    /// (which has no `{output}` of its own) back to the primary
+    /// document(s) that actually need reconverting.
+    fn primary_inputs(&self, base: &Path) -> Vec<PathBuf> {
+        WalkDir::new(base)
+            .into_iter()
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.into_path())
+            .filter(|path| path.is_file() && self.matches_input(path))
+            .collect()
+    }
+
+    /// Output path for `input`, named per this rule's `output` extension.
+    pub fn output_path(&self, input: &Path) -> PathBuf {
+        let mut output = input.to_path_buf();
+        output.set_extension(&self.output);
+        output
+    }
+
+    /// The rule's command template split into argv tokens, with
+    /// `{input}`/`{output}` filled in token-by-token. Substituting into
+    /// discrete tokens (rather than building one string for a shell to
+    /// re-parse) means a path containing shell metacharacters is just a
+    /// literal argument, never a chance to inject extra commands -- see
+    /// `convert_custom`, which runs the result directly with no shell.
+    pub fn command_args(&self, input: &Path, output: &Path) -> Vec<String> {
+        let input = input.display().to_string();
+        let output = output.display().to_string();
+
+        self.command
+            .split_whitespace()
+            .map(|token| {
+                token
+                    .replace("{input}", &input)
+                    .replace("{output}", &output)
+            })
+            .collect()
+    }
+
+    /// A short label for logging, taken from the command's program name.
+    pub fn label(&self) -> &str {
+        self.command.split_whitespace().next().unwrap_or("custom")
+    }
+}
+
+/// Declarative conversion pipeline loaded from `writekit.yaml`, in the
+/// spirit of funzzy's task list: an ordered list of rules, the first
+/// whose `input` or `change` patterns match a changed path winning.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    rules: Vec<Rule>,
+    // Directory `load` found this config in, used to resolve a rule's
+    // `change` dependencies back to the primary inputs under it -- not
+    // part of `writekit.yaml` itself.
+    #[serde(skip)]
+    base: PathBuf,
+}
+
+impl Config {
+    /// Load `writekit.yaml`/`writekit.yml` from `dir`, if present.
+    pub fn load(dir: &Path) -> Result<Option<Config>> {
+        for name in &["writekit.yaml", "writekit.yml"] {
+            let candidate = dir.join(name);
+            if candidate.is_file() {
+                let contents = fs::read_to_string(&candidate)?;
+                let mut config: Config = serde_yaml::from_str(&contents)?;
+                config.base = dir.to_path_buf();
+                return Ok(Some(config));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Look for a config next to the watch target (or its parent, if
+    /// `target` is a file) and fall back to the current directory.
+    pub fn discover(target: &Path) -> Result<Option<Config>> {
+        let dir = if target.is_dir() {
+            target
+        } else {
+            target.parent().unwrap_or_else(|| Path::new("."))
+        };
+
+        if let Some(config) = Config::load(dir)? {
+            return Ok(Some(config));
+        }
+
+        let cwd = env::current_dir()?;
+        if cwd != dir {
+            return Config::load(&cwd);
+        }
+
+        Ok(None)
+    }
+
+    /// Rule whose `input` pattern directly matches `path`, ignoring
+    /// `change` dependencies -- for callers like `handle_remove` that need
+    /// `path`'s own output, not the outputs of documents that merely
+    /// include it.
+    pub fn rule_for_input(&self, path: &Path) -> Option<&Rule> {
+        self.rules.iter().find(|rule| rule.matches_input(path))
+    }
+
+    /// Rule and concrete input path(s) a change at `path` should trigger a
+    /// conversion for. A direct `input` match converts `path` itself; a
+    /// `change` dependency match (e.g. a shared include) instead resolves
+    /// to every primary document the same rule already converts, since
+    /// the dependency has no `{output}` of its own.
+    pub fn targets_for(&self, path: &Path) -> Vec<(&Rule, PathBuf)> {
+        match self.rules.iter().find(|rule| rule.matches(path)) {
+            Some(rule) if rule.matches_input(path) => vec![(rule, path.to_path_buf())],
+            Some(rule) => rule
+                .primary_inputs(&self.base)
+                .into_iter()
+                .map(|input| (rule, input))
+                .collect(),
+            None => vec![],
+        }
+    }
+
+    /// Glob patterns for every extension a rule produces output in, e.g.
+    /// `*.pdf` for an `output: pdf` rule -- fed into `Monitor::ignore`
+    /// alongside the built-in patterns in `ignore_filter` so a custom
+    /// pipeline's own output can't re-trigger itself (or, worse, get
+    /// mistaken for a fresh source file by the built-in converters).
+    pub fn ignore_patterns(&self) -> Vec<String> {
+        self.rules
+            .iter()
+            .map(|rule| format!("*.{}", rule.output))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(input: &str, output: &str, change: &[&str], ignore: &[&str]) -> Rule {
+        Rule {
+            input: input.to_string(),
+            output: output.to_string(),
+            command: "true".to_string(),
+            change: change.iter().map(|s| s.to_string()).collect(),
+            ignore: ignore.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!(
+            "writekit-config-test-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn matches_input_requires_the_input_glob() {
+        let rule = rule("*.md", "pdf", &[], &[]);
+        assert!(rule.matches_input(Path::new("/notes/draft.md")));
+        assert!(!rule.matches_input(Path::new("/notes/draft.txt")));
+    }
+
+    #[test]
+    fn matches_change_is_not_an_input_match() {
+        let rule = rule("*.md", "pdf", &["*_includes*.md"], &[]);
+        let include = Path::new("/notes/_includes/header.md");
+
+        assert!(rule.matches_change(include));
+        assert!(!rule.matches_input(include));
+        assert!(rule.matches(include));
+    }
+
+    #[test]
+    fn ignore_carves_an_exception_out_of_input() {
+        let rule = rule("*.md", "pdf", &[], &["*.draft.md"]);
+
+        assert!(rule.matches(Path::new("/notes/final.md")));
+        assert!(!rule.matches(Path::new("/notes/final.draft.md")));
+    }
+
+    #[test]
+    fn output_path_swaps_the_extension() {
+        let rule = rule("*.md", "pdf", &[], &[]);
+        assert_eq!(
+            rule.output_path(Path::new("/notes/draft.md")),
+            PathBuf::from("/notes/draft.pdf")
+        );
+    }
+
+    #[test]
+    fn command_args_substitutes_each_token() {
+        let rule = rule("*.md", "pdf", &[], &[]);
+        let args = rule.command_args(Path::new("in.md"), Path::new("out.pdf"));
+        assert_eq!(args, vec!["true".to_string()]);
+
+        let rule = Rule {
+            command: "convert {input} -o {output}".to_string(),
+            ..rule
+        };
+        let args = rule.command_args(Path::new("in.md"), Path::new("out.pdf"));
+        assert_eq!(args, vec!["convert", "in.md", "-o", "out.pdf"]);
+    }
+
+    #[test]
+    fn targets_for_direct_input_converts_the_path_itself() {
+        let dir = temp_dir("targets-for-direct");
+        let config = Config {
+            rules: vec![rule("*.md", "pdf", &[], &[])],
+            base: dir.clone(),
+        };
+
+        let page = dir.join("page.md");
+        let targets = config.targets_for(&page);
+
+        assert_eq!(targets.len(), 1);
+        assert_eq!(targets[0].1, page);
+        assert_eq!(targets[0].0.label(), "true");
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn targets_for_change_resolves_to_primary_inputs() {
+        let dir = temp_dir("targets-for-change");
+        fs::create_dir_all(dir.join("_includes")).unwrap();
+        fs::write(dir.join("page.md"), "").unwrap();
+        fs::write(dir.join("other.txt"), "").unwrap();
+        fs::write(dir.join("_includes/header.md"), "").unwrap();
+
+        let config = Config {
+            rules: vec![rule("*.md", "pdf", &["*_includes*.md"], &[])],
+            base: dir.clone(),
+        };
+
+        let targets = config.targets_for(&dir.join("_includes/header.md"));
+
+        assert_eq!(targets.len(), 1);
+        assert_eq!(targets[0].1, dir.join("page.md"));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,137 @@
+use std::path::{Path, PathBuf};
+
+use ignore::gitignore::GitignoreBuilder;
+
+use crate::Result;
+
+/// Extensions/patterns writekit itself produces. Always applied first (and
+/// so lowest-precedence) so a markdown -> html -> pdf/png pipeline never
+/// re-enters itself, plus the usual editor backup/swap files and VCS dirs
+/// that otherwise retrigger conversions.
+const BUILTIN_IGNORES: &[&str] = &["*.pdf", "*.png", "*~", ".*.sw?", ".git/", ".hg/", ".svn/"];
+
+/// Compiled ignore rules rooted at a single watched path. Rules are
+/// ordered gitignore-style: later rules (caller patterns, then
+/// `.gitignore`/`.writekitignore` files, closest directory last) override
+/// earlier ones, and a leading `!` re-includes a path.
+pub struct IgnoreSet {
+    matcher: ignore::gitignore::Gitignore,
+}
+
+impl IgnoreSet {
+    /// Build the matcher for `root`: builtin patterns, then caller-supplied
+    /// `patterns`, then any `.gitignore`/`.writekitignore` found at or
+    /// above `root` (applied from the filesystem root down, so the
+    /// directory closest to `root` wins ties, matching how git itself
+    /// layers ignore files).
+    pub fn build(root: &Path, patterns: &[&str]) -> Result<IgnoreSet> {
+        let mut builder = GitignoreBuilder::new(root);
+
+        for pattern in BUILTIN_IGNORES {
+            builder.add_line(None, pattern)?;
+        }
+        for pattern in patterns {
+            builder.add_line(None, pattern)?;
+        }
+
+        for dir in ancestors_root_first(root) {
+            for name in &[".gitignore", ".writekitignore"] {
+                let candidate = dir.join(name);
+                if candidate.is_file() {
+                    if let Some(error) = builder.add(&candidate) {
+                        return Err(error.into());
+                    }
+                }
+            }
+        }
+
+        Ok(IgnoreSet {
+            matcher: builder.build()?,
+        })
+    }
+
+    /// True if `path` should be filtered out before reaching the handler.
+    pub fn is_ignored(&self, path: &Path) -> bool {
+        let is_dir = path.is_dir();
+        matches!(
+            self.matcher.matched_path_or_any_parents(path, is_dir),
+            ignore::Match::Ignore(_)
+        )
+    }
+}
+
+/// `root`'s ancestors, ordered from the filesystem root down to `root`.
+fn ancestors_root_first(root: &Path) -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = root.ancestors().map(Path::to_path_buf).collect();
+    dirs.reverse();
+    dirs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "writekit-ignore-test-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn builtin_patterns_ignore_generated_outputs() {
+        let root = temp_dir("builtin");
+        let set = IgnoreSet::build(&root, &[]).unwrap();
+
+        assert!(set.is_ignored(&root.join("diagram.pdf")));
+        assert!(set.is_ignored(&root.join("diagram.png")));
+        assert!(!set.is_ignored(&root.join("notes.md")));
+
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn caller_patterns_layer_on_top_of_builtins() {
+        let root = temp_dir("caller-pattern");
+        let set = IgnoreSet::build(&root, &["drafts/"]).unwrap();
+
+        assert!(set.is_ignored(&root.join("drafts/wip.md")));
+        assert!(!set.is_ignored(&root.join("notes.md")));
+
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn gitignore_negation_overrides_a_broader_ignore() {
+        let root = temp_dir("negation");
+        fs::write(root.join(".gitignore"), "*.md\n!keep.md\n").unwrap();
+
+        let set = IgnoreSet::build(&root, &[]).unwrap();
+
+        assert!(set.is_ignored(&root.join("draft.md")));
+        assert!(!set.is_ignored(&root.join("keep.md")));
+
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn closer_writekitignore_overrides_a_parent_gitignore() {
+        let root = temp_dir("precedence");
+        let sub = root.join("sub");
+        fs::create_dir_all(&sub).unwrap();
+        fs::write(root.join(".gitignore"), "*.md\n").unwrap();
+        fs::write(sub.join(".writekitignore"), "!keep.md\n").unwrap();
+
+        let set = IgnoreSet::build(&sub, &[]).unwrap();
+
+        assert!(set.is_ignored(&sub.join("draft.md")));
+        assert!(!set.is_ignored(&sub.join("keep.md")));
+
+        let _ = fs::remove_dir_all(&root);
+    }
+}
@@ -1,15 +1,25 @@
+use std::collections::HashMap;
 use std::env;
 use std::error;
 use std::fmt;
+use std::fs;
 use std::io::{BufRead, BufReader};
 use std::path::{Path, PathBuf};
 use std::process::{Child, Command, Stdio};
-use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::sync::Mutex;
 use std::thread;
 use std::time::{Duration, Instant};
 
 use indicatif::{ProgressBar, ProgressStyle};
 use notify::{self, DebouncedEvent as Event, FsEventWatcher, RecursiveMode, Watcher};
+use walkdir::WalkDir;
+
+mod config;
+mod ignore_filter;
+
+pub use config::Config;
+use ignore_filter::IgnoreSet;
 
 // Standard "error-boxing" Result type:
 type Result<T> = ::std::result::Result<T, Box<dyn error::Error>>;
@@ -19,17 +29,21 @@ pub struct Args {
     pub display: bool,
     pub verbose: bool,
     pub quiet: bool,
+    pub build: bool,
+    pub debounce: u64,
 }
 
 impl fmt::Display for Args {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
-            "Arguments: target='{}' display={} verbose={} quiet={}",
+            "Arguments: target='{}' display={} verbose={} quiet={} build={} debounce={}",
             self.target.display(),
             self.display,
             self.verbose,
-            self.quiet
+            self.quiet,
+            self.build,
+            self.debounce
         )
     }
 }
@@ -39,6 +53,12 @@ impl Args {
         let display = matches.is_present("display");
         let verbose = matches.is_present("verbose");
         let quiet = matches.is_present("quiet");
+        let build = matches.is_present("build");
+        let debounce = matches
+            .value_of("debounce")
+            .map(str::parse)
+            .transpose()?
+            .unwrap_or(1_000);
 
         // Read "target" argument, a file or directory to be watched for changes.
         // If none provided, set to current directory (in which script was invoked).
@@ -52,6 +72,8 @@ impl Args {
             display,
             verbose,
             quiet,
+            build,
+            debounce,
         })
     }
 }
@@ -144,10 +166,82 @@ impl<'a> Loading<'a> {
     }
 }
 
+// Tracks the conversion child process currently in flight for each output
+// path, so that a new change for the same source can kill the stale
+// conversion (and its whole process group, since e.g. wkhtmltopdf spawns
+// helpers) before starting its replacement, rather than letting both race
+// to write the same file.
+pub struct ProcessTracker {
+    children: Mutex<HashMap<PathBuf, Child>>,
+}
+
+impl ProcessTracker {
+    pub fn new() -> ProcessTracker {
+        ProcessTracker {
+            children: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Kill whatever conversion is currently running for `output`, if any.
+    // Call this before spawning the replacement child for `output`.
+    pub fn supersede(&self, output: &Path) {
+        if let Some(mut stale) = self.children.lock().unwrap().remove(output) {
+            kill_process_group(&mut stale);
+        }
+    }
+
+    // Register `child` as the conversion now in flight for `output`.
+    pub fn track(&self, output: &Path, child: Child) {
+        self.children
+            .lock()
+            .unwrap()
+            .insert(output.to_path_buf(), child);
+    }
+}
+
+#[cfg(unix)]
+fn new_process_group(command: &mut Command) {
+    use std::os::unix::process::CommandExt;
+
+    // Put the child (and anything it spawns) in its own process group,
+    // so it can be killed as a unit rather than just the immediate pid:
+    unsafe {
+        command.pre_exec(|| {
+            if unsafe { libc::setpgid(0, 0) } != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+}
+
+#[cfg(not(unix))]
+fn new_process_group(_command: &mut Command) {}
+
+#[cfg(unix)]
+fn kill_process_group(child: &mut Child) {
+    let pgid = child.id() as libc::pid_t;
+    unsafe {
+        libc::kill(-pgid, libc::SIGTERM);
+    }
+    let _ = child.wait();
+}
+
+#[cfg(not(unix))]
+fn kill_process_group(child: &mut Child) {
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
 pub struct Monitor {
+    tx: Sender<Event>,
     rx: Receiver<Event>,
     watcher: FsEventWatcher,
     paths: Vec<PathBuf>,
+    ignore_patterns: Vec<String>,
+    debounce: Duration,
+    bulk_load: bool,
+    config: Option<Config>,
 }
 
 impl Monitor {
@@ -155,11 +249,16 @@ impl Monitor {
         // Create a channel to communicate with Notify watcher:
         let (tx, rx) = mpsc::channel();
         let debounce = Duration::from_millis(debounce_ms);
-        let watcher = notify::watcher(tx, debounce)?;
+        let watcher = notify::watcher(tx.clone(), debounce)?;
         Ok(Monitor {
+            tx,
             rx,
             watcher,
             paths: vec![],
+            ignore_patterns: vec![],
+            debounce,
+            bulk_load: false,
+            config: None,
         })
     }
 
@@ -171,6 +270,33 @@ impl Monitor {
         self
     }
 
+    // Extra gitignore-style patterns to filter events by, on top of the
+    // `.gitignore`/`.writekitignore` files auto-loaded for each watched
+    // path in `watch()` below:
+    // e.g. Monitor::new(1_000).path("~/notes").ignore(&["drafts/"]).watch(...)
+    pub fn ignore(mut self, patterns: &[&str]) -> Monitor {
+        self.ignore_patterns
+            .extend(patterns.iter().map(|pattern| pattern.to_string()));
+        self
+    }
+
+    // When set, `watch()` walks each watched path once up front and feeds
+    // every already-existing convertible file in as a synthetic `Create`
+    // event, so a directory of already-written files gets built on
+    // startup instead of only on the next edit:
+    pub fn bulk_load(mut self, enabled: bool) -> Monitor {
+        self.bulk_load = enabled;
+        self
+    }
+
+    // The same declarative config `handle_write`/`handle_remove` convert
+    // through, so bulk-load's `is_convertible` check recognizes files a
+    // custom rule's `input` covers, not just the built-in extensions:
+    pub fn config(mut self, config: Option<Config>) -> Monitor {
+        self.config = config;
+        self
+    }
+
     pub fn watch<F: Fn(Result<Event>)>(mut self, handler: F) -> Result<()> {
         // Watch for file changes in target directory via Notify:
         // https://docs.rs/notify/4.0.10/notify
@@ -181,25 +307,112 @@ impl Monitor {
             self.watcher.watch(path, RecursiveMode::Recursive)?;
         }
 
-        loop {
-            let event_result = self.rx.recv();
+        // Compile one ignore matcher per watched path, combining builtin
+        // patterns, caller-supplied patterns and any discovered
+        // `.gitignore`/`.writekitignore` files -- see `ignore_filter`.
+        let patterns: Vec<&str> = self.ignore_patterns.iter().map(String::as_str).collect();
+        let ignores: Vec<IgnoreSet> = self
+            .paths
+            .iter()
+            .map(|path| IgnoreSet::build(path, &patterns))
+            .collect::<Result<_>>()?;
+
+        if self.bulk_load {
+            // Mirrors rust-analyzer's VFS "BulkLoadRoot": walk the tree on
+            // a dedicated thread and feed discovered files into the same
+            // channel the watcher uses, so the existing Create handling
+            // (and Loading bar) picks them up unchanged:
+            let tx = self.tx.clone();
+            let paths = self.paths.clone();
+            let config = self.config.take();
+            thread::spawn(move || {
+                for root in &paths {
+                    for entry in WalkDir::new(root).into_iter().filter_map(|e| e.ok()) {
+                        let path = entry.path();
+                        if path.is_file() && is_convertible(path, config.as_ref()) {
+                            let _ = tx.send(Event::Create(path.to_path_buf()));
+                        }
+                    }
+                }
+            });
+        }
+
+        // Coalesced per-path state not yet delivered to the handler.
+        // Later events for the same path simply overwrite its entry, so
+        // once the channel goes quiet we report each path exactly once,
+        // in its current (quiescent) state -- rust-analyzer's VFS takes
+        // the same approach to bursts of FS events.
+        let mut pending: HashMap<PathBuf, PathState> = HashMap::new();
 
-            if let Ok(ref event) = event_result {
-                if let Event::Create(ref path) = event {
-                    // New file created -- ensure it's watched:
-                    self.watcher.watch(path, RecursiveMode::Recursive)?;
+        loop {
+            match self.rx.recv_timeout(self.debounce) {
+                Ok(event) => {
+                    let normalized = match event {
+                        Event::Remove(path) => {
+                            // The watch on `path` itself is gone now that
+                            // it no longer exists -- watch its parent so
+                            // the editor's recreation of it isn't missed:
+                            if let Some(parent) = path.parent() {
+                                let _ = self.watcher.watch(parent, RecursiveMode::NonRecursive);
+                            }
+                            Some((path, PathState::Removed))
+                        }
+                        Event::Create(path) => {
+                            // A later `Create` for a path we already saw a
+                            // `Remove` for this quiet period just overwrites
+                            // its `pending` entry back to `Changed` below --
+                            // coalescing the remove-then-create pair an
+                            // atomic save produces into the single "file
+                            // changed" signal it actually represents:
+                            self.watcher.watch(&path, RecursiveMode::Recursive)?;
+                            Some((path, PathState::Changed))
+                        }
+                        Event::Write(path) => Some((path, PathState::Changed)),
+                        Event::Rename(_from, to) => {
+                            // Same atomic-save pattern, but surfaced by
+                            // notify as a rename rather than a separate
+                            // remove/create pair:
+                            Some((to, PathState::Changed))
+                        }
+                        Event::Error(error, _) => {
+                            handler(Err(error.into()));
+                            None
+                        }
+                        _ => None,
+                    };
+
+                    if let Some((path, state)) = normalized {
+                        if ignores.iter().any(|set| set.is_ignored(&path)) {
+                            continue;
+                        }
+                        pending.insert(path, state);
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    // Channel idle for a full quiet period: drain the
+                    // quiescent state, one signal per path.
+                    for (path, state) in pending.drain() {
+                        match state {
+                            PathState::Changed => handler(Ok(Event::Write(path))),
+                            PathState::Removed => handler(Ok(Event::Remove(path))),
+                        }
+                    }
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    handler(Err("file watcher disconnected".into()));
+                    return Ok(());
                 }
             }
-
-            match event_result {
-                Ok(event) => handler(Ok(event)),
-                Err(error) => handler(Err(error.into())),
-                // convert into boxed error
-            };
         }
     }
 }
 
+#[derive(Clone, Copy)]
+enum PathState {
+    Changed,
+    Removed,
+}
+
 // Use strum to allow Converter enum to map to conversion CLI commands:
 // https://docs.rs/strum/0.20.0/strum/
 #[derive(strum_macros::Display)]
@@ -214,13 +427,94 @@ enum Converter {
     HtmlToPng,
 }
 
+// Files `handle_write` below knows how to convert -- either a built-in
+// extension, or a path some `Config` rule's `input` matches directly --
+// used by bulk-load to pick out the files worth feeding in as synthetic
+// `Create` events.
+fn is_convertible(path: &Path, config: Option<&Config>) -> bool {
+    let is_builtin = matches!(
+        path.extension().and_then(|ext| ext.to_str()),
+        Some("md") | Some("adoc") | Some("html")
+    );
+
+    is_builtin || config.map_or(false, |config| config.rule_for_input(path).is_some())
+}
+
+// Mirror image of `handle_write`'s extension match: when a watched source
+// is removed for good (no recreation arrives to coalesce it back into a
+// `Write`, see `Monitor::watch`), clean up whatever it previously produced
+// rather than leaving stale generated files behind.
+pub fn handle_remove(
+    path: &Path,
+    quiet: bool,
+    config: Option<&Config>,
+    tracker: &ProcessTracker,
+) -> Result<()> {
+    let outputs: Vec<PathBuf> = match config.and_then(|config| config.rule_for_input(path)) {
+        Some(rule) => vec![rule.output_path(path)],
+        None => match path.extension().and_then(|ext| ext.to_str()) {
+            Some("md") | Some("adoc") => vec![path.with_extension("html")],
+            Some("html") => vec![path.with_extension("pdf"), path.with_extension("png")],
+            _ => vec![],
+        },
+    };
+
+    for output in outputs {
+        // A conversion for this exact output may still be in flight --
+        // kill it first so it can't recreate the file right after we
+        // delete it:
+        tracker.supersede(&output);
+
+        if output.is_file() {
+            fs::remove_file(&output)?;
+            if !quiet {
+                println!("{} removed -> {}", path.display(), output.display());
+            }
+        }
+    }
+
+    Ok(())
+}
+
 pub fn handle_write(
     path: &PathBuf,
     loading: &mut Loading,
     display: bool,
     verbose: bool,
     quiet: bool,
+    config: Option<&Config>,
+    tracker: &ProcessTracker,
 ) -> Result<()> {
+    let targets = config
+        .map(|config| config.targets_for(path))
+        .unwrap_or_default();
+
+    if !targets.is_empty() {
+        // A `change` match (e.g. a shared include) resolves to every
+        // primary document the rule converts, not just `path` itself --
+        // rebuild each independently, same as a direct match rebuilds one:
+        for (rule, input) in targets {
+            loading.start();
+
+            let output = rule.output_path(&input);
+
+            tracker.supersede(&output);
+            let mut proc = convert_custom(rule, &input, &output, display, quiet)?;
+            handle_proc(&mut proc, rule.label(), verbose);
+            tracker.track(&output, proc);
+
+            if !quiet && output.extension().and_then(|ext| ext.to_str()) == Some("png") {
+                loading.finish();
+
+                if display {
+                    Command::new("imgcat").arg(&output).spawn()?;
+                }
+            }
+        }
+
+        return Ok(());
+    }
+
     if let Some(ext) = path.extension() {
         match ext.to_str() {
             Some("md") => {
@@ -229,18 +523,17 @@ pub fn handle_write(
                 let mut outhtml = path.clone();
                 outhtml.set_extension("html");
 
-                handle_proc(
-                    convert(
-                        Converter::MarkdownToHtml,
-                        &path,
-                        &outhtml,
-                        display,
-                        verbose,
-                        quiet,
-                    )?,
+                tracker.supersede(&outhtml);
+                let mut proc = convert(
                     Converter::MarkdownToHtml,
+                    &path,
+                    &outhtml,
+                    display,
                     verbose,
-                );
+                    quiet,
+                )?;
+                handle_proc(&mut proc, Converter::MarkdownToHtml, verbose);
+                tracker.track(&outhtml, proc);
             }
             Some("adoc") => {
                 loading.start();
@@ -248,24 +541,24 @@ pub fn handle_write(
                 let mut outhtml = path.clone();
                 outhtml.set_extension("html");
 
-                handle_proc(
-                    convert(
-                        Converter::AsciidocToHtml,
-                        &path,
-                        &outhtml,
-                        display,
-                        verbose,
-                        quiet,
-                    )?,
+                tracker.supersede(&outhtml);
+                let mut proc = convert(
                     Converter::AsciidocToHtml,
+                    &path,
+                    &outhtml,
+                    display,
                     verbose,
-                );
+                    quiet,
+                )?;
+                handle_proc(&mut proc, Converter::AsciidocToHtml, verbose);
+                tracker.track(&outhtml, proc);
             }
             Some("html") => {
                 let mut outpdf = path.clone();
                 outpdf.set_extension("pdf");
 
-                let proc_pdf = convert(
+                tracker.supersede(&outpdf);
+                let mut proc_pdf = convert(
                     Converter::HtmlToPdf,
                     &path,
                     &outpdf,
@@ -277,7 +570,8 @@ pub fn handle_write(
                 let mut outpng = path.clone();
                 outpng.set_extension("png");
 
-                let proc_png = convert(
+                tracker.supersede(&outpng);
+                let mut proc_png = convert(
                     Converter::HtmlToPng,
                     &path,
                     &outpng,
@@ -291,10 +585,34 @@ pub fn handle_write(
                     println!("");
                 }
 
-                handle_proc(proc_pdf, Converter::HtmlToPdf, verbose);
-                handle_proc(proc_png, Converter::HtmlToPng, verbose);
+                handle_proc(&mut proc_pdf, Converter::HtmlToPdf, verbose);
+                handle_proc(&mut proc_png, Converter::HtmlToPng, verbose);
+
+                tracker.track(&outpdf, proc_pdf);
+
+                // The generated png is covered by the builtin ignore
+                // patterns (see ignore_filter) so writing it no longer
+                // reaches the `Some("png")` arm below via a filesystem
+                // event -- signal "conversion complete" directly here,
+                // once the png child itself has exited, instead.
+                let png_finished = proc_png.wait().map(|status| status.success());
+                tracker.track(&outpng, proc_png);
+
+                if !quiet {
+                    loading.finish();
+
+                    if display && matches!(png_finished, Ok(true)) {
+                        Command::new("imgcat").arg(&outpng).spawn()?;
+                    }
+                }
             }
             Some("png") => {
+                // Only reached for a .png path that gets past the ignore
+                // filter (e.g. re-included by a `!` pattern) and that no
+                // writekit.yaml rule targets -- rule outputs are handled
+                // in the custom branch above, and the builtin pipeline
+                // signals completion from the "html" arm above instead,
+                // since generated pngs are ignored by the watcher.
                 if !quiet {
                     loading.finish();
 
@@ -309,29 +627,32 @@ pub fn handle_write(
     Ok(())
 }
 
-fn handle_proc(proc: Child, converter: Converter, verbose: bool) {
+// Takes `proc` by mutable reference (rather than consuming it) so the
+// caller can go on to hand it to `ProcessTracker` for supersession once
+// this returns.
+fn handle_proc(proc: &mut Child, label: impl fmt::Display, verbose: bool) {
     if verbose {
-        if let Some(stdout) = proc.stdout {
+        if let Some(stdout) = proc.stdout.take() {
             BufReader::new(stdout).lines().for_each(|line| {
-                println!(". . . {} [stdout] . . .", converter.to_string());
+                println!(". . . {} [stdout] . . .", label);
                 println!(
                     "{}",
                     line.unwrap_or_else(|_| format!(
                         "error: failed to process stdout for {}",
-                        converter.to_string()
+                        label
                     ))
                 );
             });
         }
 
-        if let Some(stderr) = proc.stderr {
+        if let Some(stderr) = proc.stderr.take() {
             BufReader::new(stderr).lines().for_each(|line| {
-                println!(". . . {} [stderr] . . .", converter.to_string());
+                println!(". . . {} [stderr] . . .", label);
                 println!(
                     "{}",
                     line.unwrap_or_else(|_| format!(
                         "error: failed to process stderr for {}",
-                        converter.to_string()
+                        label
                     ))
                 );
             });
@@ -354,6 +675,7 @@ fn convert(
     let mut command = Command::new(converter.to_string());
 
     command.stdout(Stdio::piped()).stderr(Stdio::piped());
+    new_process_group(&mut command);
 
     match converter {
         Converter::MarkdownToHtml | Converter::AsciidocToHtml => {
@@ -384,3 +706,34 @@ fn convert(
 
     Ok(proc)
 }
+
+// Run a `writekit.yaml` rule's command template for `input` -> `output`,
+// as a direct argv invocation (no shell) so a filename with shell
+// metacharacters can't smuggle extra commands onto the line -- reuses the
+// same `handle_proc` plumbing as the built-in converters.
+fn convert_custom(
+    rule: &config::Rule,
+    input: &Path,
+    output: &Path,
+    display: bool,
+    quiet: bool,
+) -> Result<Child> {
+    if !quiet && !display {
+        println!("{} -> {}", input.display(), output.display());
+    }
+
+    let mut args = rule.command_args(input, output);
+    if args.is_empty() {
+        return Err("rule command is empty".into());
+    }
+    let program = args.remove(0);
+
+    let mut command = Command::new(program);
+    command
+        .args(args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    new_process_group(&mut command);
+
+    Ok(command.spawn()?)
+}
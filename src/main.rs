@@ -5,9 +5,9 @@ use std::env;
 use std::sync::{Arc, Mutex};
 
 use clap::clap_app;
-use notify::DebouncedEvent::{Create, Write};
+use notify::DebouncedEvent::{Create, Remove, Write};
 
-use writekit::{handle_write, Args, Loading, Monitor};
+use writekit::{handle_remove, handle_write, Args, Config, Loading, Monitor, ProcessTracker};
 
 // Get config values directly from Cargo.toml so they _never_ get out of sync:
 const VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -28,6 +28,8 @@ fn main() {
             (@arg display: --display -d)
             (@arg verbose: --verbose -v)
             (@arg quiet: --quiet -q)
+            (@arg build: --build -b "convert all existing files once on startup, before watching")
+            (@arg debounce: --debounce +takes_value "milliseconds to wait for changes to go quiet before converting [default: 1000]")
         )
         // Args constructor accepts a clap::ArgMatches object:
         .get_matches(),
@@ -47,10 +49,33 @@ fn main() {
     // calls in closure below, so use mutex to safely manage mutable sharing.
     let loading_arc = Arc::new(Mutex::new(Loading::new().clear()));
 
-    Monitor::new(1_000) // debounce milliseconds
+    // Custom conversion pipeline, if a writekit.yaml is found near the
+    // target (or in the current directory); falls back to the built-in
+    // converters in handle_write when absent.
+    let config =
+        Config::discover(&args.target).unwrap_or_else(|error| panic!("error: {:?}", error));
+
+    // Tracks in-flight conversion children so a rapid re-save supersedes
+    // (kills) the stale conversion instead of racing it for the output:
+    let tracker = ProcessTracker::new();
+
+    // A custom rule's own output (e.g. `*.html` for a non-builtin rule)
+    // needs the same anti-feedback-loop treatment chunk0-1 gives the
+    // built-in converters' `*.pdf`/`*.png`, or it'll re-trigger itself
+    // (or get mistaken for a fresh source by the built-in pipeline):
+    let ignore_patterns: Vec<String> = config
+        .as_ref()
+        .map(Config::ignore_patterns)
+        .unwrap_or_default();
+    let ignore_patterns: Vec<&str> = ignore_patterns.iter().map(String::as_str).collect();
+
+    Monitor::new(args.debounce) // quiet period, in milliseconds, before a burst of changes is drained
         // On Monitor initialization error -- panic to exit script:
         .unwrap_or_else(|error| panic!("error: {:?}", error))
         .path(&args.target)
+        .ignore(&ignore_patterns)
+        .config(config.clone())
+        .bulk_load(args.build)
         .watch(|event_result| match event_result {
             Ok(event) => {
                 if args.verbose {
@@ -69,12 +94,21 @@ fn main() {
                                     args.display,
                                     args.verbose,
                                     args.quiet,
+                                    config.as_ref(),
+                                    &tracker,
                                 )
                                 .unwrap_or_else(|error| eprintln!("error: {:?}", error));
                             }
                             Err(error) => eprintln!("error: {:?}", error),
                         }
                     }
+                    Remove(path) => {
+                        // Source removed and not recreated before the
+                        // quiet period elapsed -- clean up whatever it
+                        // last produced:
+                        handle_remove(&path, args.quiet, config.as_ref(), &tracker)
+                            .unwrap_or_else(|error| eprintln!("error: {:?}", error));
+                    }
                     _ => (),
                 }
             }